@@ -0,0 +1,643 @@
+//! `forward` subcommand: ssh `-L`/`-R` style TCP and UDP port forwarding over a tied channel.
+//!
+//! TCP forwarding multiplexes many simultaneous accepted connections over one
+//! [`turntie::ReliableTurnTie`] using a small framing protocol (connection-open, data tagged
+//! with a logical connection id, connection-close), since the underlying channel is otherwise
+//! lossy and unordered. UDP forwarding has no notion of a "connection" to multiplex, so it is
+//! kept to a single relayed flow directly over the raw [`turntie::TurnTie`] datagram pipe: an
+//! announcement packet carries the dial target, retransmitted on a timer until the peer acks it
+//! (the raw pipe can drop it like any other packet), and every following packet is one datagram.
+//!
+//! Both ends of a `forward` invocation must agree on `--udp`/TCP, since that choice decides
+//! which of the two transports (and wire formats) is used underneath.
+
+use std::{
+    collections::HashMap,
+    net::SocketAddr,
+    sync::{
+        atomic::{AtomicU32, Ordering},
+        Arc, Mutex,
+    },
+};
+
+use anyhow::Context;
+use bytes::{Buf, BufMut, Bytes, BytesMut};
+use futures::{SinkExt, StreamExt};
+use tokio::{
+    io::{AsyncReadExt, AsyncWriteExt},
+    net::{TcpListener, TcpStream, UdpSocket},
+    sync::mpsc,
+    time::{interval, Duration},
+};
+use tokio_util::codec::{Decoder, Encoder, FramedRead, FramedWrite};
+use turntie::{ReliableTurnTie, TurnTie};
+
+/// Direction of a forwarded descriptor, ssh `-L`/`-R` style.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Direction {
+    /// `-L`: this process binds locally and forwards accepted connections to the peer, which
+    /// dials the target.
+    LocalToRemote,
+    /// `-R`: the peer binds on our behalf; we dial the target for connections it accepts.
+    RemoteToLocal,
+}
+
+/// Transport protocol of a forwarded descriptor.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Protocol {
+    Tcp,
+    Udp,
+}
+
+/// A parsed `-L`/`-R` descriptor: `BIND_ADDR:BIND_PORT:TARGET_HOST:TARGET_PORT`.
+#[derive(Clone, Debug)]
+pub struct ForwardDescriptor {
+    pub direction: Direction,
+    pub protocol: Protocol,
+    pub bind: SocketAddr,
+    pub target: String,
+}
+
+impl ForwardDescriptor {
+    pub fn parse(direction: Direction, protocol: Protocol, spec: &str) -> anyhow::Result<ForwardDescriptor> {
+        let mut parts = spec.split(':');
+        let bind_host = parts
+            .next()
+            .with_context(|| format!("missing bind host in forward spec {spec:?}"))?;
+        let bind_port = parts
+            .next()
+            .with_context(|| format!("missing bind port in forward spec {spec:?}"))?;
+        let target_host = parts
+            .next()
+            .with_context(|| format!("missing target host in forward spec {spec:?}"))?;
+        let target_port = parts
+            .next()
+            .with_context(|| format!("missing target port in forward spec {spec:?}"))?;
+        anyhow::ensure!(
+            parts.next().is_none(),
+            "too many fields in forward spec {spec:?}, expected BIND_ADDR:BIND_PORT:TARGET_HOST:TARGET_PORT"
+        );
+        let bind: SocketAddr = format!("{bind_host}:{bind_port}")
+            .parse()
+            .with_context(|| format!("parsing bind address in forward spec {spec:?}"))?;
+        Ok(ForwardDescriptor {
+            direction,
+            protocol,
+            bind,
+            target: format!("{target_host}:{target_port}"),
+        })
+    }
+}
+
+/// A frame of the TCP forwarding multiplexing protocol.
+enum Frame {
+    /// Sent by the `-R` side at startup, asking the peer to bind and accept on its behalf.
+    Listen {
+        bind: SocketAddr,
+        target: String,
+    },
+    /// A new logical connection was accepted; the receiver should dial `target`.
+    Open {
+        conn_id: u32,
+        target: String,
+    },
+    Data {
+        conn_id: u32,
+        data: Bytes,
+    },
+    Close {
+        conn_id: u32,
+    },
+}
+
+const FRAME_LISTEN: u8 = 0;
+const FRAME_OPEN: u8 = 1;
+const FRAME_DATA: u8 = 2;
+const FRAME_CLOSE: u8 = 3;
+
+struct MuxCodec;
+
+impl Encoder<Frame> for MuxCodec {
+    type Error = std::io::Error;
+
+    fn encode(&mut self, item: Frame, dst: &mut BytesMut) -> std::io::Result<()> {
+        match item {
+            Frame::Listen { bind, target } => {
+                dst.put_u8(FRAME_LISTEN);
+                let bind = bind.to_string();
+                dst.put_u16(bind.len() as u16);
+                dst.put_slice(bind.as_bytes());
+                dst.put_u16(target.len() as u16);
+                dst.put_slice(target.as_bytes());
+            }
+            Frame::Open { conn_id, target } => {
+                dst.put_u8(FRAME_OPEN);
+                dst.put_u32(conn_id);
+                dst.put_u16(target.len() as u16);
+                dst.put_slice(target.as_bytes());
+            }
+            Frame::Data { conn_id, data } => {
+                dst.put_u8(FRAME_DATA);
+                dst.put_u32(conn_id);
+                dst.put_u32(data.len() as u32);
+                dst.put_slice(&data);
+            }
+            Frame::Close { conn_id } => {
+                dst.put_u8(FRAME_CLOSE);
+                dst.put_u32(conn_id);
+            }
+        }
+        Ok(())
+    }
+}
+
+impl Decoder for MuxCodec {
+    type Item = Frame;
+    type Error = std::io::Error;
+
+    fn decode(&mut self, src: &mut BytesMut) -> std::io::Result<Option<Frame>> {
+        if src.is_empty() {
+            return Ok(None);
+        }
+        let bad = || std::io::Error::new(std::io::ErrorKind::InvalidData, "malformed forwarding frame");
+
+        let total_len = match src[0] {
+            FRAME_LISTEN | FRAME_OPEN => {
+                let header_len = if src[0] == FRAME_LISTEN { 3 } else { 7 };
+                if src.len() < header_len {
+                    return Ok(None);
+                }
+                let first_len = u16::from_be_bytes([src[header_len - 2], src[header_len - 1]]) as usize;
+                if src.len() < header_len + first_len {
+                    return Ok(None);
+                }
+                if src[0] == FRAME_LISTEN {
+                    if src.len() < header_len + first_len + 2 {
+                        return Ok(None);
+                    }
+                    let second_len_at = header_len + first_len;
+                    let second_len =
+                        u16::from_be_bytes([src[second_len_at], src[second_len_at + 1]]) as usize;
+                    header_len + first_len + 2 + second_len
+                } else {
+                    header_len + first_len
+                }
+            }
+            FRAME_DATA => {
+                if src.len() < 9 {
+                    return Ok(None);
+                }
+                let len = u32::from_be_bytes([src[5], src[6], src[7], src[8]]) as usize;
+                9 + len
+            }
+            FRAME_CLOSE => 5,
+            _ => return Err(bad()),
+        };
+        if src.len() < total_len {
+            return Ok(None);
+        }
+
+        let mut frame = src.split_to(total_len);
+        let kind = frame.get_u8();
+        Ok(Some(match kind {
+            FRAME_LISTEN => {
+                let bind_len = frame.get_u16() as usize;
+                let bind = std::str::from_utf8(&frame.split_to(bind_len))
+                    .ok()
+                    .and_then(|s| s.parse().ok())
+                    .ok_or_else(bad)?;
+                let target_len = frame.get_u16() as usize;
+                let target = String::from_utf8(frame.split_to(target_len).to_vec()).map_err(|_| bad())?;
+                Frame::Listen { bind, target }
+            }
+            FRAME_OPEN => {
+                let conn_id = frame.get_u32();
+                let target_len = frame.get_u16() as usize;
+                let target = String::from_utf8(frame.split_to(target_len).to_vec()).map_err(|_| bad())?;
+                Frame::Open { conn_id, target }
+            }
+            FRAME_DATA => {
+                let conn_id = frame.get_u32();
+                let _len = frame.get_u32();
+                Frame::Data {
+                    conn_id,
+                    data: frame.freeze(),
+                }
+            }
+            FRAME_CLOSE => Frame::Close {
+                conn_id: frame.get_u32(),
+            },
+            _ => return Err(bad()),
+        }))
+    }
+}
+
+type Connections = Arc<Mutex<HashMap<u32, mpsc::UnboundedSender<Bytes>>>>;
+
+/// Run TCP forwarding: multiplexes accepted connections (ours via `descriptor`, and any the peer
+/// sends us) over `channel` using [`MuxCodec`].
+pub async fn run_tcp_forward(
+    channel: ReliableTurnTie,
+    descriptor: Option<ForwardDescriptor>,
+) -> anyhow::Result<()> {
+    let (read_half, write_half) = tokio::io::split(channel);
+    let mut reader = FramedRead::new(read_half, MuxCodec);
+    let mut writer = FramedWrite::new(write_half, MuxCodec);
+
+    let (frame_tx, mut frame_rx) = mpsc::unbounded_channel::<Frame>();
+    tokio::spawn(async move {
+        while let Some(frame) = frame_rx.recv().await {
+            if writer.send(frame).await.is_err() {
+                break;
+            }
+        }
+    });
+
+    let next_conn_id = Arc::new(AtomicU32::new(1));
+    let connections: Connections = Arc::new(Mutex::new(HashMap::new()));
+
+    if let Some(desc) = &descriptor {
+        anyhow::ensure!(desc.protocol == Protocol::Tcp, "expected a TCP forward descriptor");
+        match desc.direction {
+            Direction::LocalToRemote => {
+                spawn_tcp_listener(desc.bind, desc.target.clone(), frame_tx.clone(), next_conn_id.clone(), connections.clone())
+                    .await?;
+            }
+            Direction::RemoteToLocal => {
+                frame_tx
+                    .send(Frame::Listen {
+                        bind: desc.bind,
+                        target: desc.target.clone(),
+                    })
+                    .ok();
+            }
+        }
+    }
+
+    while let Some(frame) = reader.next().await {
+        match frame? {
+            Frame::Listen { bind, target } => {
+                spawn_tcp_listener(bind, target, frame_tx.clone(), next_conn_id.clone(), connections.clone()).await?;
+            }
+            Frame::Open { conn_id, target } => {
+                // Register the sender before spawning the dial, not after it connects: any
+                // `Frame::Data` for `conn_id` that arrives while the dial is still in flight (the
+                // opening bytes almost always do) must have somewhere to buffer rather than being
+                // silently dropped because `connections` doesn't know about `conn_id` yet.
+                let (incoming_tx, incoming_rx) = mpsc::unbounded_channel::<Bytes>();
+                connections.lock().unwrap().insert(conn_id, incoming_tx);
+
+                let frame_tx = frame_tx.clone();
+                let connections = connections.clone();
+                tokio::spawn(async move {
+                    if let Err(e) =
+                        dial_and_relay_tcp(conn_id, &target, frame_tx, connections, incoming_rx).await
+                    {
+                        eprintln!("turntie forward: connection {conn_id} to {target} failed: {e:#}");
+                    }
+                });
+            }
+            Frame::Data { conn_id, data } => {
+                let sender = connections.lock().unwrap().get(&conn_id).cloned();
+                if let Some(sender) = sender {
+                    let _ = sender.send(data);
+                }
+            }
+            Frame::Close { conn_id } => {
+                connections.lock().unwrap().remove(&conn_id);
+            }
+        }
+    }
+
+    Ok(())
+}
+
+async fn spawn_tcp_listener(
+    bind: SocketAddr,
+    target: String,
+    frame_tx: mpsc::UnboundedSender<Frame>,
+    next_conn_id: Arc<AtomicU32>,
+    connections: Connections,
+) -> anyhow::Result<()> {
+    let listener = TcpListener::bind(bind)
+        .await
+        .with_context(|| format!("binding forward listener on {bind}"))?;
+    tokio::spawn(async move {
+        loop {
+            let (stream, _) = match listener.accept().await {
+                Ok(x) => x,
+                Err(e) => {
+                    eprintln!("turntie forward: accept on {bind} failed: {e:#}");
+                    continue;
+                }
+            };
+            let conn_id = next_conn_id.fetch_add(1, Ordering::Relaxed);
+            // Same ordering requirement as the `Frame::Open` handler on the receiving end: register
+            // before the peer can possibly send data for this `conn_id`, i.e. before the `Open` frame
+            // is even sent.
+            let (incoming_tx, incoming_rx) = mpsc::unbounded_channel::<Bytes>();
+            connections.lock().unwrap().insert(conn_id, incoming_tx);
+            if frame_tx
+                .send(Frame::Open {
+                    conn_id,
+                    target: target.clone(),
+                })
+                .is_err()
+            {
+                connections.lock().unwrap().remove(&conn_id);
+                break;
+            }
+            let frame_tx = frame_tx.clone();
+            let connections = connections.clone();
+            tokio::spawn(async move {
+                if let Err(e) = relay_tcp(conn_id, stream, frame_tx, connections, incoming_rx).await {
+                    eprintln!("turntie forward: connection {conn_id} failed: {e:#}");
+                }
+            });
+        }
+    });
+    Ok(())
+}
+
+async fn dial_and_relay_tcp(
+    conn_id: u32,
+    target: &str,
+    frame_tx: mpsc::UnboundedSender<Frame>,
+    connections: Connections,
+    incoming_rx: mpsc::UnboundedReceiver<Bytes>,
+) -> anyhow::Result<()> {
+    let stream = TcpStream::connect(target)
+        .await
+        .with_context(|| format!("dialing forward target {target}"))?;
+    relay_tcp(conn_id, stream, frame_tx, connections, incoming_rx).await
+}
+
+/// Pump bytes between `stream` and the mux channel for one logical connection, until either side
+/// closes. `incoming_rx` is the receiving half of the sender already registered in `connections`
+/// for `conn_id`, so any data framed for this connection before the caller could spawn us is
+/// buffered in the channel rather than lost.
+async fn relay_tcp(
+    conn_id: u32,
+    mut stream: TcpStream,
+    frame_tx: mpsc::UnboundedSender<Frame>,
+    connections: Connections,
+    mut incoming_rx: mpsc::UnboundedReceiver<Bytes>,
+) -> anyhow::Result<()> {
+    let (mut read_half, mut write_half) = stream.split();
+    let mut buf = [0u8; 16 * 1024];
+
+    let result = loop {
+        tokio::select! {
+            n = read_half.read(&mut buf) => {
+                match n {
+                    Ok(0) => break Ok(()),
+                    Ok(n) => {
+                        if frame_tx.send(Frame::Data { conn_id, data: Bytes::copy_from_slice(&buf[..n]) }).is_err() {
+                            break Ok(());
+                        }
+                    }
+                    Err(e) => break Err(e.into()),
+                }
+            }
+            data = incoming_rx.recv() => {
+                match data {
+                    Some(data) => {
+                        if write_half.write_all(&data).await.is_err() {
+                            break Ok(());
+                        }
+                    }
+                    None => break Ok(()),
+                }
+            }
+        }
+    };
+
+    connections.lock().unwrap().remove(&conn_id);
+    let _ = frame_tx.send(Frame::Close { conn_id });
+    result
+}
+
+const UDP_ANNOUNCE: u8 = 0;
+const UDP_DATA: u8 = 1;
+const UDP_ANNOUNCE_ACK: u8 = 2;
+
+/// How often to resend the announcement while waiting for [`UDP_ANNOUNCE_ACK`], since the raw
+/// [`TurnTie`] pipe can drop it like any other packet.
+const ANNOUNCE_RETRANSMIT_INTERVAL: Duration = Duration::from_millis(500);
+
+/// Run UDP forwarding: a single relayed flow directly over the raw [`TurnTie`] datagram pipe.
+/// See the module docs for why UDP forwarding forgoes the connection-multiplexing protocol.
+pub async fn run_udp_forward(channel: TurnTie, descriptor: Option<ForwardDescriptor>) -> anyhow::Result<()> {
+    let (mut sink, mut stream) = channel.split();
+
+    match descriptor {
+        Some(desc) => {
+            anyhow::ensure!(desc.protocol == Protocol::Udp, "expected a UDP forward descriptor");
+            anyhow::ensure!(
+                desc.direction == Direction::LocalToRemote,
+                "UDP forwarding only supports -L; run plain 'forward --udp' on the other end"
+            );
+
+            let local = UdpSocket::bind(desc.bind)
+                .await
+                .with_context(|| format!("binding forward listener on {}", desc.bind))?;
+
+            let mut announce = BytesMut::new();
+            announce.put_u8(UDP_ANNOUNCE);
+            announce.put_slice(desc.target.as_bytes());
+            let announce = announce.freeze();
+            sink.send(announce.clone()).await?;
+
+            let mut acked = false;
+            let mut retransmit = interval(ANNOUNCE_RETRANSMIT_INTERVAL);
+            retransmit.tick().await; // first tick fires immediately; we just sent it above.
+
+            let mut client_addr = None::<SocketAddr>;
+            let mut buf = [0u8; 16 * 1024];
+            loop {
+                tokio::select! {
+                    _ = retransmit.tick(), if !acked => {
+                        sink.send(announce.clone()).await?;
+                    }
+                    recvd = local.recv_from(&mut buf) => {
+                        let (n, from) = recvd?;
+                        client_addr = Some(from);
+                        let mut packet = BytesMut::with_capacity(1 + n);
+                        packet.put_u8(UDP_DATA);
+                        packet.put_slice(&buf[..n]);
+                        sink.send(packet.freeze()).await?;
+                    }
+                    packet = stream.next() => {
+                        let packet = match packet {
+                            Some(p) => p?,
+                            None => return Ok(()),
+                        };
+                        match packet.first() {
+                            Some(&UDP_ANNOUNCE_ACK) => acked = true,
+                            Some(&UDP_DATA) => {
+                                if let Some(addr) = client_addr {
+                                    local.send_to(&packet[1..], addr).await?;
+                                }
+                            }
+                            _ => {}
+                        }
+                    }
+                }
+            }
+        }
+        None => {
+            // Passive agent: wait for the announcement, dial the target, then relay. The active
+            // side keeps resending the announcement until acked, so ack it as soon as it arrives.
+            let target = loop {
+                match stream.next().await {
+                    Some(packet) => {
+                        let packet = packet?;
+                        if packet.first() == Some(&UDP_ANNOUNCE) {
+                            sink.send(Bytes::from_static(&[UDP_ANNOUNCE_ACK])).await?;
+                            break String::from_utf8_lossy(&packet[1..]).into_owned();
+                        }
+                    }
+                    None => return Ok(()),
+                }
+            };
+
+            let remote = UdpSocket::bind(("0.0.0.0", 0)).await?;
+            remote
+                .connect(target.as_str())
+                .await
+                .with_context(|| format!("dialing forward target {target}"))?;
+
+            let mut buf = [0u8; 16 * 1024];
+            loop {
+                tokio::select! {
+                    packet = stream.next() => {
+                        let packet = match packet {
+                            Some(p) => p?,
+                            None => return Ok(()),
+                        };
+                        match packet.first() {
+                            Some(&UDP_DATA) => {
+                                remote.send(&packet[1..]).await?;
+                            }
+                            Some(&UDP_ANNOUNCE) => {
+                                // Our ack was lost and the active side is still retransmitting.
+                                sink.send(Bytes::from_static(&[UDP_ANNOUNCE_ACK])).await?;
+                            }
+                            _ => {}
+                        }
+                    }
+                    n = remote.recv(&mut buf) => {
+                        let n = n?;
+                        let mut packet = BytesMut::with_capacity(1 + n);
+                        packet.put_u8(UDP_DATA);
+                        packet.put_slice(&buf[..n]);
+                        sink.send(packet.freeze()).await?;
+                    }
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn round_trip(frame: Frame) -> Frame {
+        let mut codec = MuxCodec;
+        let mut buf = BytesMut::new();
+        codec.encode(frame, &mut buf).unwrap();
+        codec.decode(&mut buf).unwrap().expect("a full frame should decode")
+    }
+
+    #[test]
+    fn listen_frame_round_trips() {
+        match round_trip(Frame::Listen {
+            bind: "127.0.0.1:8080".parse().unwrap(),
+            target: "example.com:80".to_string(),
+        }) {
+            Frame::Listen { bind, target } => {
+                assert_eq!(bind, "127.0.0.1:8080".parse().unwrap());
+                assert_eq!(target, "example.com:80");
+            }
+            _ => panic!("expected a listen frame"),
+        }
+    }
+
+    #[test]
+    fn open_frame_round_trips() {
+        match round_trip(Frame::Open {
+            conn_id: 7,
+            target: "example.com:443".to_string(),
+        }) {
+            Frame::Open { conn_id, target } => {
+                assert_eq!(conn_id, 7);
+                assert_eq!(target, "example.com:443");
+            }
+            _ => panic!("expected an open frame"),
+        }
+    }
+
+    #[test]
+    fn data_frame_round_trips() {
+        match round_trip(Frame::Data {
+            conn_id: 3,
+            data: Bytes::from_static(b"hello world"),
+        }) {
+            Frame::Data { conn_id, data } => {
+                assert_eq!(conn_id, 3);
+                assert_eq!(&data[..], b"hello world");
+            }
+            _ => panic!("expected a data frame"),
+        }
+    }
+
+    #[test]
+    fn close_frame_round_trips() {
+        match round_trip(Frame::Close { conn_id: 42 }) {
+            Frame::Close { conn_id } => assert_eq!(conn_id, 42),
+            _ => panic!("expected a close frame"),
+        }
+    }
+
+    #[test]
+    fn decode_waits_for_a_full_frame_before_returning() {
+        let mut codec = MuxCodec;
+        let mut buf = BytesMut::new();
+        codec
+            .encode(
+                Frame::Data {
+                    conn_id: 1,
+                    data: Bytes::from_static(b"partial"),
+                },
+                &mut buf,
+            )
+            .unwrap();
+
+        // Feed the decoder only a byte-truncated prefix: it must not misinterpret the partial
+        // length/data as a different, smaller frame, and must leave the bytes it was given alone.
+        let full_len = buf.len();
+        let mut truncated = buf.split_to(full_len - 1);
+        let truncated_len = truncated.len();
+        assert!(codec.decode(&mut truncated).unwrap().is_none());
+        assert_eq!(truncated.len(), truncated_len);
+    }
+
+    #[test]
+    fn decodes_two_frames_back_to_back_from_one_buffer() {
+        let mut codec = MuxCodec;
+        let mut buf = BytesMut::new();
+        codec.encode(Frame::Close { conn_id: 1 }, &mut buf).unwrap();
+        codec.encode(Frame::Close { conn_id: 2 }, &mut buf).unwrap();
+
+        match codec.decode(&mut buf).unwrap().unwrap() {
+            Frame::Close { conn_id } => assert_eq!(conn_id, 1),
+            _ => panic!("expected a close frame"),
+        }
+        match codec.decode(&mut buf).unwrap().unwrap() {
+            Frame::Close { conn_id } => assert_eq!(conn_id, 2),
+            _ => panic!("expected a close frame"),
+        }
+        assert!(buf.is_empty());
+    }
+}
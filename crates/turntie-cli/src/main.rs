@@ -5,6 +5,8 @@ use bytes::Bytes;
 use futures::{future::try_join, SinkExt, StreamExt, TryStreamExt};
 use tokio_util::codec::{FramedRead, FramedWrite, LinesCodec};
 
+mod forward;
+
 /// Use TURN server as a communication channel with movable ends
 #[derive(FromArgs)]
 /// Top-level command.
@@ -35,6 +37,11 @@ struct Tie {
     /// password to authenticate on TURN server with
     #[argh(positional)]
     password: String,
+
+    /// generate a shared salt so both ends can encrypt the channel with a passphrase given to
+    /// 'connect --encrypt'
+    #[argh(switch)]
+    encrypt: bool,
 }
 
 /// Connect to one of the endpoints created by 'turntie tie' and exchange stdin/stdout lines with the peer which connected to the other endpoint.
@@ -44,6 +51,39 @@ struct Connect {
     /// serialized data describing the channel end
     #[argh(positional)]
     specifier: String,
+
+    /// passphrase to decrypt/encrypt the channel with; must match the other end and the
+    /// specifier must have been created with 'tie --encrypt'
+    #[argh(option)]
+    encrypt: Option<String>,
+
+    #[argh(subcommand)]
+    mode: Option<ConnectMode>,
+}
+
+#[derive(FromArgs)]
+#[argh(subcommand)]
+enum ConnectMode {
+    Forward(Forward),
+}
+
+/// Forward local or remote TCP/UDP connections over the tied channel, ssh '-L'/'-R' style.
+#[derive(FromArgs)]
+#[argh(subcommand, name = "forward")]
+struct Forward {
+    /// bind locally and forward accepted connections to a target dialed by the peer:
+    /// BIND_ADDR:BIND_PORT:TARGET_HOST:TARGET_PORT
+    #[argh(option, short = 'L')]
+    local_to_remote: Option<String>,
+
+    /// ask the peer to bind and forward its accepted connections to a target dialed by us:
+    /// BIND_ADDR:BIND_PORT:TARGET_HOST:TARGET_PORT
+    #[argh(option, short = 'R')]
+    remote_to_local: Option<String>,
+
+    /// forward UDP instead of TCP; both ends of 'forward' must agree on this flag
+    #[argh(switch)]
+    udp: bool,
 }
 
 #[tokio::main(flavor = "current_thread")]
@@ -51,10 +91,44 @@ async fn main() -> anyhow::Result<()> {
     let cmd: Cmd = argh::from_env();
     match cmd.cmd {
         CmdEnum::Tie(opts) => {
-            let (s1, s2) = turntie::tie(opts.turn_server, opts.username, opts.password).await?;
+            let (s1, s2) =
+                turntie::tie(opts.turn_server, opts.username, opts.password, opts.encrypt)
+                    .await?;
             println!("{}", s1);
             println!("{}", s2);
         }
+        CmdEnum::Connect(opts) if opts.mode.is_some() => {
+            let ConnectMode::Forward(f) = opts.mode.unwrap();
+            anyhow::ensure!(
+                opts.encrypt.is_none(),
+                "--encrypt is not yet supported together with 'forward'"
+            );
+            let protocol = if f.udp { forward::Protocol::Udp } else { forward::Protocol::Tcp };
+            let descriptor = match (&f.local_to_remote, &f.remote_to_local) {
+                (Some(_), Some(_)) => anyhow::bail!("-L and -R are mutually exclusive"),
+                (Some(spec), None) => Some(forward::ForwardDescriptor::parse(
+                    forward::Direction::LocalToRemote,
+                    protocol,
+                    spec,
+                )?),
+                (None, Some(spec)) => Some(forward::ForwardDescriptor::parse(
+                    forward::Direction::RemoteToLocal,
+                    protocol,
+                    spec,
+                )?),
+                (None, None) => None,
+            };
+
+            let c = turntie::connect(&opts.specifier).await?;
+            match protocol {
+                forward::Protocol::Tcp => {
+                    forward::run_tcp_forward(turntie::ReliableTurnTie::new(c), descriptor).await?;
+                }
+                forward::Protocol::Udp => {
+                    forward::run_udp_forward(c, descriptor).await?;
+                }
+            }
+        }
         CmdEnum::Connect(opts) => {
             let c = turntie::connect(&opts.specifier).await?;
 
@@ -67,22 +141,48 @@ async fn main() -> anyhow::Result<()> {
             let r = r.err_into();
             let w = w.sink_err_into();
 
-            let (cw, cr) = c.split();
-
-            let f1 = cr
-                .map_ok(|b| {
-                    let s: String = String::from_utf8_lossy(b.as_ref()).into_owned();
-                    s
-                })
-                .forward(w);
-            let f2 = r
-                .map_ok(|line| {
-                    let b: Bytes = line.into();
-                    b
-                })
-                .forward(cw);
-            let f = try_join(f1, f2);
-            f.await?;
+            match opts.encrypt {
+                Some(passphrase) => {
+                    let salt = turntie::connect_salt(&opts.specifier)?.ok_or_else(|| {
+                        anyhow::anyhow!(
+                            "--encrypt was given but this specifier was not created with 'tie --encrypt'"
+                        )
+                    })?;
+                    let c = turntie::EncryptedTurnTie::new(c, &passphrase, &salt);
+                    let (cw, cr) = c.split();
+
+                    let f1 = cr
+                        .map_ok(|b| {
+                            let s: String = String::from_utf8_lossy(b.as_ref()).into_owned();
+                            s
+                        })
+                        .forward(w);
+                    let f2 = r
+                        .map_ok(|line| {
+                            let b: Bytes = line.into();
+                            b
+                        })
+                        .forward(cw);
+                    try_join(f1, f2).await?;
+                }
+                None => {
+                    let (cw, cr) = c.split();
+
+                    let f1 = cr
+                        .map_ok(|b| {
+                            let s: String = String::from_utf8_lossy(b.as_ref()).into_owned();
+                            s
+                        })
+                        .forward(w);
+                    let f2 = r
+                        .map_ok(|line| {
+                            let b: Bytes = line.into();
+                            b
+                        })
+                        .forward(cw);
+                    try_join(f1, f2).await?;
+                }
+            }
         }
     }
     Ok(())
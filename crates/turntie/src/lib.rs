@@ -5,10 +5,25 @@
 //!
 //! Should be used from Tokio loop.
 //!
-//! Does not offer reliability, protection against eavesdropping or attacks, fragmentation; one write to the sink = one UDP packet.
-//! 
+//! [`TurnTie`] itself is unreliable and unordered: one write to the sink = one UDP packet, which
+//! may be lost, duplicated or reordered. Wrap it in [`ReliableTurnTie`] for a reliable, ordered,
+//! fragmenting byte stream, in [`EncryptedTurnTie`] for protection against eavesdropping, or in
+//! [`MobileTurnTie`] to automatically rebind and resume the allocation across network changes.
+//!
 //! Specifiers contain username and password in cleartext.
 
+mod crypto;
+mod mobility;
+#[cfg(feature = "quic")]
+mod quic;
+mod reliable;
+
+pub use crypto::EncryptedTurnTie;
+pub use mobility::{MobileTurnTie, Status as MobilityStatus};
+#[cfg(feature = "quic")]
+pub use quic::connect_quic;
+pub use reliable::ReliableTurnTie;
+
 use std::{
     net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr},
     task::Poll, io::Write,
@@ -20,6 +35,7 @@ use bytes::Bytes;
 use flate2::{Compression, write::{ZlibEncoder, ZlibDecoder}};
 use futures::{Sink, SinkExt, Stream, StreamExt};
 use pin_project::pin_project;
+use rand::RngCore;
 use serde::{Deserialize, Serialize};
 use tokio::{net::UdpSocket, select};
 use turnclient::{
@@ -35,10 +51,16 @@ struct Data {
     nonce: String,
     mobility_ticket: Vec<u8>,
     counterpart: SocketAddr,
+    /// Present when the peers want to use [`EncryptedTurnTie`]; both ends derive the same key
+    /// from this salt plus a passphrase shared out of band.
+    salt: Option<Vec<u8>>,
+    /// Which side of [`tie`] this specifier is: only meaningful to `connect_quic` (behind the
+    /// `quic` feature), which needs exactly one of the two peers to act as the QUIC server.
+    quic_is_server: bool,
 }
 
 impl Data {
-    pub fn new(turn_server: SocketAddr, username: String, password: String, state: ExportedParameters, counterpart: SocketAddr) -> Data {
+    pub fn new(turn_server: SocketAddr, username: String, password: String, state: ExportedParameters, counterpart: SocketAddr, salt: Option<Vec<u8>>, quic_is_server: bool) -> Data {
         Data {
             turn_server,
             username,
@@ -47,6 +69,8 @@ impl Data {
             nonce: state.nonce,
             mobility_ticket: state.mobility_ticket,
             counterpart,
+            salt,
+            quic_is_server,
         }
     }
     pub fn serialize(&self) -> String {
@@ -64,11 +88,16 @@ impl Data {
     }
 }
 
-/// Create a pair of allocations and serialize their parameters to string blobs
+/// Create a pair of allocations and serialize their parameters to string blobs.
+///
+/// If `encrypt` is set, a random salt is generated and embedded in both specifiers so that the
+/// two `connect`ing peers can each derive the same [`EncryptedTurnTie`] key from a passphrase
+/// shared out of band (the passphrase itself never goes into the specifier).
 pub async fn tie(
     turn_server: SocketAddr,
     username: String,
     password: String,
+    encrypt: bool,
 ) -> anyhow::Result<(String, String)> {
     let mut t1 = TurnClientBuilder::new(turn_server, username.clone(), password.clone());
     let mut t2 = TurnClientBuilder::new(turn_server, username.clone(), password.clone());
@@ -169,8 +198,18 @@ pub async fn tie(
     let params1 = c1.export_state();
     let params2 = c2.export_state();
 
-    let spec1 = Data::new(turn_server, username.clone(), password.clone(), params1, addr2.unwrap());
-    let spec2 = Data::new(turn_server, username, password, params2, addr1.unwrap());
+    let salt = if encrypt {
+        let mut salt = vec![0u8; 16];
+        rand::thread_rng().fill_bytes(&mut salt);
+        Some(salt)
+    } else {
+        None
+    };
+
+    // Arbitrary but fixed: the first specifier returned always plays the QUIC server role for
+    // connect_quic, so the two ends never have to agree on this out of band.
+    let spec1 = Data::new(turn_server, username.clone(), password.clone(), params1, addr2.unwrap(), salt.clone(), true);
+    let spec2 = Data::new(turn_server, username, password, params2, addr1.unwrap(), salt, false);
 
     Ok((spec1.serialize(), spec2.serialize()))
 }
@@ -208,6 +247,30 @@ pub async fn connect(specifier: &str) -> anyhow::Result<TurnTie> {
     })
 }
 
+/// Retrieve the salt embedded in a specifier by [`tie`], if it was created with `encrypt` set.
+///
+/// Use this together with a passphrase shared out of band to build an [`EncryptedTurnTie`]
+/// around the [`TurnTie`] returned by [`connect`].
+pub fn connect_salt(specifier: &str) -> anyhow::Result<Option<Vec<u8>>> {
+    Ok(Data::deserialize(specifier)?.salt)
+}
+
+/// Like [`connect`], but wraps the result in a [`MobileTurnTie`] that transparently rebinds and
+/// resumes the allocation (via the same mobility ticket) if the local socket ever breaks.
+pub async fn connect_mobile(specifier: &str) -> anyhow::Result<MobileTurnTie> {
+    let data = Data::deserialize(specifier)?;
+    let info = mobility::ReconnectInfo {
+        turn_server: data.turn_server,
+        username: data.username.clone(),
+        password: data.password.clone(),
+        counterpart: data.counterpart,
+    };
+
+    let turntie = connect(specifier).await?;
+    let params = turntie.export_state();
+    Ok(MobileTurnTie::new(turntie, info, params))
+}
+
 #[pin_project]
 pub struct TurnTie {
     #[pin]
@@ -215,6 +278,21 @@ pub struct TurnTie {
     counterpart: SocketAddr,
 }
 
+impl TurnTie {
+    pub(crate) fn from_parts(turnclient: TurnClient, counterpart: SocketAddr) -> TurnTie {
+        TurnTie {
+            turnclient,
+            counterpart,
+        }
+    }
+
+    /// Export the current realm/nonce/mobility ticket, e.g. to hand to [`MobileTurnTie`] for a
+    /// later rebind.
+    pub fn export_state(&self) -> ExportedParameters {
+        self.turnclient.export_state()
+    }
+}
+
 impl Stream for TurnTie {
     type Item = anyhow::Result<Bytes>;
 
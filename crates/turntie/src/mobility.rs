@@ -0,0 +1,338 @@
+//! Supervised reconnection on top of [`TurnTie`], using the RFC 8016 mobility this crate already
+//! requires. A bare `TurnTie` dies the moment its local UDP socket breaks (Wi-Fi change, IP
+//! change, NAT rebind); [`MobileTurnTie`] instead binds a fresh socket and resumes the same
+//! allocation via `restore_from_exported_parameters`, so the Sink/Stream keeps working
+//! transparently across the gap.
+//!
+//! Detection and retries only happen while the stream is polled, matching [`crate::reliable`]'s
+//! caveat about needing to keep driving the stream.
+
+use std::{
+    collections::VecDeque,
+    future::Future,
+    net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr},
+    pin::Pin,
+    sync::Arc,
+    task::{Context, Poll},
+};
+
+use bytes::Bytes;
+use futures::{Sink, Stream};
+use tokio::{
+    net::UdpSocket,
+    sync::watch,
+    time::{Duration, Instant},
+};
+use turnclient::{ExportedParameters, TurnClientBuilder};
+
+use crate::TurnTie;
+
+/// How many rebind attempts in a row may fail before the allocation is considered genuinely
+/// dead and the stream errors out.
+const MAX_RECONNECT_ATTEMPTS: u32 = 8;
+const INITIAL_BACKOFF: Duration = Duration::from_millis(500);
+const MAX_BACKOFF: Duration = Duration::from_secs(30);
+/// No incoming traffic for this long is treated the same as a socket error.
+const DEFAULT_IDLE_TIMEOUT: Duration = Duration::from_secs(45);
+/// Outgoing writes made while reconnecting are buffered up to this many packets; older ones are
+/// dropped first to bound memory use.
+const MAX_BUFFERED_PACKETS: usize = 256;
+
+/// Connection status reported by [`MobileTurnTie::status`].
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Status {
+    Connected,
+    Reconnecting,
+    Failed,
+}
+
+/// Everything needed to bind a fresh [`TurnTie`] back onto the same allocation.
+pub(crate) struct ReconnectInfo {
+    pub(crate) turn_server: SocketAddr,
+    pub(crate) username: String,
+    pub(crate) password: String,
+    pub(crate) counterpart: SocketAddr,
+}
+
+async fn rebind(info: Arc<ReconnectInfo>, params: Arc<ExportedParameters>) -> anyhow::Result<TurnTie> {
+    let mut builder =
+        TurnClientBuilder::new(info.turn_server, info.username.clone(), info.password.clone());
+    builder.enable_mobility = true;
+
+    let neutral_sockaddr = match info.turn_server {
+        SocketAddr::V4(_) => SocketAddr::new(IpAddr::V4(Ipv4Addr::UNSPECIFIED), 0),
+        SocketAddr::V6(_) => SocketAddr::new(IpAddr::V6(Ipv6Addr::UNSPECIFIED), 0),
+    };
+    let socket = UdpSocket::bind(neutral_sockaddr).await?;
+    let turnclient = builder.restore_from_exported_parameters(socket, &params)?;
+    Ok(TurnTie::from_parts(turnclient, info.counterpart))
+}
+
+enum Conn {
+    Connected(Pin<Box<TurnTie>>),
+    Reconnecting {
+        future: Pin<Box<dyn Future<Output = anyhow::Result<TurnTie>> + Send>>,
+        attempt: u32,
+        backoff: Duration,
+    },
+    /// Gave up after [`MAX_RECONNECT_ATTEMPTS`]; the allocation is treated as genuinely dead.
+    Failed,
+}
+
+/// Wraps [`TurnTie`] with automatic rebind-and-resume: on a socket error or idle timeout, binds a
+/// fresh [`tokio::net::UdpSocket`] and resumes the same TURN allocation via its mobility ticket,
+/// buffering outgoing writes across the gap. See the module docs for the polling caveat.
+pub struct MobileTurnTie {
+    conn: Conn,
+    info: Arc<ReconnectInfo>,
+    params: Arc<ExportedParameters>,
+    outgoing_buffer: VecDeque<Bytes>,
+    last_activity: Instant,
+    idle_timeout: Duration,
+    /// Armed to `last_activity + idle_timeout` and polled from `poll_next`/`poll_flush` so the
+    /// idle timeout fires even while the inner stream is quietly `Pending`, instead of only being
+    /// checked on the next incidental wakeup (which may never come on a dead socket).
+    idle_timer: Pin<Box<tokio::time::Sleep>>,
+    status_tx: watch::Sender<Status>,
+    status_rx: watch::Receiver<Status>,
+}
+
+impl MobileTurnTie {
+    pub(crate) fn new(turntie: TurnTie, info: ReconnectInfo, params: ExportedParameters) -> MobileTurnTie {
+        let (status_tx, status_rx) = watch::channel(Status::Connected);
+        MobileTurnTie {
+            conn: Conn::Connected(Box::pin(turntie)),
+            info: Arc::new(info),
+            params: Arc::new(params),
+            outgoing_buffer: VecDeque::new(),
+            last_activity: Instant::now(),
+            idle_timeout: DEFAULT_IDLE_TIMEOUT,
+            idle_timer: Box::pin(tokio::time::sleep(DEFAULT_IDLE_TIMEOUT)),
+            status_tx,
+            status_rx,
+        }
+    }
+
+    /// Override the idle/keepalive timeout used to proactively trigger a rebind even when the
+    /// socket hasn't reported an error (defaults to 45 seconds).
+    pub fn set_idle_timeout(&mut self, timeout: Duration) {
+        self.idle_timeout = timeout;
+        self.rearm_idle_timer();
+    }
+
+    /// Record fresh activity and push the idle deadline back out from now.
+    fn rearm_idle_timer(&mut self) {
+        self.last_activity = Instant::now();
+        self.idle_timer.as_mut().reset(self.last_activity + self.idle_timeout);
+    }
+
+    /// Observe connection status transitions (connected/reconnecting/failed).
+    pub fn status(&self) -> watch::Receiver<Status> {
+        self.status_rx.clone()
+    }
+
+    fn buffer(&mut self, item: Bytes) {
+        if self.outgoing_buffer.len() >= MAX_BUFFERED_PACKETS {
+            self.outgoing_buffer.pop_front();
+        }
+        self.outgoing_buffer.push_back(item);
+    }
+
+    /// While connected, hand every buffered item to the inner sink's `start_send`, without
+    /// waiting on `poll_ready` in between. Returns the error of the first failed send, if any
+    /// (the buffer item itself is dropped on error).
+    fn drain_buffer(&mut self) -> Option<anyhow::Error> {
+        if let Conn::Connected(turntie) = &mut self.conn {
+            while let Some(item) = self.outgoing_buffer.pop_front() {
+                if let Err(e) = turntie.as_mut().start_send(item) {
+                    return Some(e);
+                }
+            }
+        }
+        None
+    }
+
+    /// `error` is the trigger (a socket error or the idle timeout) but isn't surfaced anywhere:
+    /// [`Self::status`] only reports that a reconnect is under way, not what triggered it, and
+    /// nothing is written to stderr.
+    fn begin_reconnect(&mut self, _error: anyhow::Error) {
+        if !matches!(self.conn, Conn::Connected(_)) {
+            return; // already reconnecting (or given up); don't reset the backoff
+        }
+        self.schedule_reconnect(1, INITIAL_BACKOFF);
+    }
+
+    fn schedule_reconnect(&mut self, attempt: u32, backoff: Duration) {
+        let info = Arc::clone(&self.info);
+        let params = Arc::clone(&self.params);
+        let future: Pin<Box<dyn Future<Output = anyhow::Result<TurnTie>> + Send>> = Box::pin(async move {
+            tokio::time::sleep(backoff).await;
+            rebind(info, params).await
+        });
+        self.conn = Conn::Reconnecting {
+            future,
+            attempt,
+            backoff,
+        };
+        let _ = self.status_tx.send(Status::Reconnecting);
+    }
+
+    /// Advance the reconnect state machine. Returns `Poll::Ready(true)` once reconnected (the
+    /// caller should retry its I/O), `Poll::Ready(false)` if reconnection has been permanently
+    /// given up on, or `Poll::Pending` if still waiting.
+    fn drive_reconnect(&mut self, cx: &mut Context<'_>) -> Poll<bool> {
+        loop {
+            match std::mem::replace(&mut self.conn, Conn::Failed) {
+                Conn::Connected(t) => {
+                    self.conn = Conn::Connected(t);
+                    return Poll::Ready(true);
+                }
+                Conn::Failed => {
+                    self.conn = Conn::Failed;
+                    return Poll::Ready(false);
+                }
+                Conn::Reconnecting {
+                    mut future,
+                    attempt,
+                    backoff,
+                } => match future.as_mut().poll(cx) {
+                    Poll::Pending => {
+                        self.conn = Conn::Reconnecting {
+                            future,
+                            attempt,
+                            backoff,
+                        };
+                        return Poll::Pending;
+                    }
+                    Poll::Ready(Ok(turntie)) => {
+                        self.params = Arc::new(turntie.export_state());
+                        self.conn = Conn::Connected(Box::pin(turntie));
+                        self.rearm_idle_timer();
+                        let _ = self.status_tx.send(Status::Connected);
+                        if let Some(e) = self.drain_buffer() {
+                            self.begin_reconnect(e);
+                        }
+                        return Poll::Ready(true);
+                    }
+                    Poll::Ready(Err(_)) => {
+                        if attempt >= MAX_RECONNECT_ATTEMPTS {
+                            self.conn = Conn::Failed;
+                            let _ = self.status_tx.send(Status::Failed);
+                            return Poll::Ready(false);
+                        }
+                        self.schedule_reconnect(attempt + 1, (backoff * 2).min(MAX_BACKOFF));
+                    }
+                },
+            }
+        }
+    }
+}
+
+impl Stream for MobileTurnTie {
+    type Item = anyhow::Result<Bytes>;
+
+    fn poll_next(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        let this = self.get_mut();
+        loop {
+            let result = if let Conn::Connected(turntie) = &mut this.conn {
+                // Always poll the idle timer (not just check `last_activity.elapsed()`) so its
+                // waker stays registered: if `turntie.poll_next` below returns `Pending` on a
+                // silently dead socket, something still needs to wake this task when the deadline
+                // passes.
+                if this.idle_timer.as_mut().poll(cx).is_ready() {
+                    Some(Poll::Ready(Some(Err(anyhow::anyhow!(
+                        "turntie mobility: idle/keepalive timeout"
+                    )))))
+                } else {
+                    Some(turntie.as_mut().poll_next(cx))
+                }
+            } else {
+                None
+            };
+
+            match result {
+                Some(Poll::Ready(Some(Err(e)))) => this.begin_reconnect(e),
+                Some(Poll::Ready(Some(Ok(bytes)))) => {
+                    this.rearm_idle_timer();
+                    return Poll::Ready(Some(Ok(bytes)));
+                }
+                Some(other) => return other,
+                None => {}
+            }
+
+            match this.drive_reconnect(cx) {
+                Poll::Pending => return Poll::Pending,
+                Poll::Ready(true) => continue,
+                Poll::Ready(false) => {
+                    return Poll::Ready(Some(Err(anyhow::anyhow!(
+                        "turntie mobility: giving up after {MAX_RECONNECT_ATTEMPTS} failed reconnect attempts"
+                    ))))
+                }
+            }
+        }
+    }
+}
+
+impl Sink<Bytes> for MobileTurnTie {
+    type Error = anyhow::Error;
+
+    fn poll_ready(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        let this = self.get_mut();
+        if let Conn::Connected(turntie) = &mut this.conn {
+            return turntie.as_mut().poll_ready(cx);
+        }
+        Poll::Ready(Ok(()))
+    }
+
+    fn start_send(self: Pin<&mut Self>, item: Bytes) -> Result<(), Self::Error> {
+        let this = self.get_mut();
+        let failed = match &mut this.conn {
+            Conn::Connected(turntie) => turntie.as_mut().start_send(item).err(),
+            _ => {
+                this.buffer(item);
+                None
+            }
+        };
+        if let Some(e) = failed {
+            this.begin_reconnect(e);
+        }
+        Ok(())
+    }
+
+    fn poll_flush(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        let this = self.get_mut();
+        loop {
+            if let Some(e) = this.drain_buffer() {
+                this.begin_reconnect(e);
+            }
+            if matches!(this.conn, Conn::Connected(_)) {
+                // Keep the idle timer's waker registered on this side too: a caller that only
+                // drives writes (never reads) would otherwise never observe the timeout.
+                if this.idle_timer.as_mut().poll(cx).is_ready() {
+                    this.begin_reconnect(anyhow::anyhow!("turntie mobility: idle/keepalive timeout"));
+                    continue;
+                }
+            }
+            if let Conn::Connected(turntie) = &mut this.conn {
+                return turntie.as_mut().poll_flush(cx);
+            }
+            match this.drive_reconnect(cx) {
+                Poll::Pending => return Poll::Pending,
+                Poll::Ready(true) => continue,
+                Poll::Ready(false) => {
+                    return Poll::Ready(Err(anyhow::anyhow!(
+                        "turntie mobility: giving up after {MAX_RECONNECT_ATTEMPTS} failed reconnect attempts"
+                    )))
+                }
+            }
+        }
+    }
+
+    fn poll_close(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        let this = self.get_mut();
+        if let Conn::Connected(turntie) = &mut this.conn {
+            return turntie.as_mut().poll_close(cx);
+        }
+        Poll::Ready(Ok(()))
+    }
+}